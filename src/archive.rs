@@ -0,0 +1,69 @@
+//! Persists every fetched article into a local FTS5 search index, so past
+//! issues stay searchable after the generated MOBI/EPUB files are gone.
+
+use std::path::Path;
+
+use rusqlite::{Connection, Result as SqlResult};
+
+use content;
+use Article;
+
+const ARCHIVE_FILE: &'static str = "archive.db";
+
+/// Opens (creating if necessary) the FTS5 archive database in `dir`.
+pub fn open(dir: &Path) -> SqlResult<Connection> {
+    let conn = try!(Connection::open(dir.join(ARCHIVE_FILE)));
+    try!(conn.execute_batch(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS articles USING fts5(
+            paper, issue_date, refid, title, byline, blurb, content
+        );"));
+    Ok(conn)
+}
+
+/// Indexes `articles` from one fetch under `paper`/`issue_date`, with
+/// markup stripped from the content so FTS5 ranks on prose rather than tags.
+/// Any rows already archived for this `paper`/`issue_date` are replaced
+/// first, so a `--force` re-run doesn't duplicate that issue forever.
+pub fn index_articles(conn: &Connection, paper: &str, issue_date: &str, articles: &[Article]) -> SqlResult<()> {
+    try!(conn.execute(
+        "DELETE FROM articles WHERE paper = ?1 AND issue_date = ?2",
+        &[&paper, &issue_date]));
+
+    for a in articles {
+        let plain = content::plain_text(&a.content);
+        try!(conn.execute(
+            "INSERT INTO articles (paper, issue_date, refid, title, byline, blurb, content)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            &[&paper, &issue_date, &a.refid, &a.title, &a.byline, &a.blurb, &plain]));
+    }
+    Ok(())
+}
+
+pub struct SearchHit {
+    pub paper: String,
+    pub issue_date: String,
+    pub title: String,
+    pub snippet: String,
+}
+
+/// Runs an FTS5 `MATCH` query against the archive, most relevant first.
+pub fn search(conn: &Connection, query: &str) -> SqlResult<Vec<SearchHit>> {
+    let mut stmt = try!(conn.prepare(
+        "SELECT paper, issue_date, title, snippet(articles, 6, '[', ']', '...', 12)
+         FROM articles WHERE articles MATCH ?1 ORDER BY rank"));
+
+    let rows = try!(stmt.query_map(&[&query], |row| {
+        SearchHit {
+            paper: row.get(0),
+            issue_date: row.get(1),
+            title: row.get(2),
+            snippet: row.get(3),
+        }
+    }));
+
+    let mut hits = Vec::new();
+    for row in rows {
+        hits.push(try!(row));
+    }
+    Ok(hits)
+}
@@ -0,0 +1,94 @@
+//! Tracks the last processed `refid` pattern per paper, so `convert_papers`
+//! can skip a paper whose refid hasn't moved since the previous run.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use rusqlite::{Connection, Result as SqlResult};
+
+pub trait Cache {
+    /// The refid pattern stored for `paper` on the previous run, if any.
+    fn last_refid(&self, paper: &str) -> Option<String>;
+
+    /// Records `refid` as the latest pattern seen for `paper`.
+    fn set_last_refid(&mut self, paper: &str, refid: &str);
+}
+
+pub struct SqliteCache {
+    conn: Connection,
+}
+
+impl SqliteCache {
+    pub fn open(path: &Path) -> SqlResult<SqliteCache> {
+        let conn = try!(Connection::open(path));
+        try!(conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS last_refid (paper TEXT PRIMARY KEY, refid TEXT NOT NULL);"));
+        Ok(SqliteCache { conn: conn })
+    }
+}
+
+impl Cache for SqliteCache {
+    fn last_refid(&self, paper: &str) -> Option<String> {
+        self.conn.query_row(
+            "SELECT refid FROM last_refid WHERE paper = ?1",
+            &[&paper], |row| row.get(0)).ok()
+    }
+
+    fn set_last_refid(&mut self, paper: &str, refid: &str) {
+        self.conn.execute(
+            "INSERT INTO last_refid (paper, refid) VALUES (?1, ?2)
+             ON CONFLICT(paper) DO UPDATE SET refid = excluded.refid",
+            &[&paper, &refid]).unwrap();
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryCache {
+    entries: HashMap<String, String>,
+}
+
+impl MemoryCache {
+    pub fn new() -> MemoryCache {
+        MemoryCache { entries: HashMap::new() }
+    }
+}
+
+impl Cache for MemoryCache {
+    fn last_refid(&self, paper: &str) -> Option<String> {
+        self.entries.get(paper).cloned()
+    }
+
+    fn set_last_refid(&mut self, paper: &str, refid: &str) {
+        self.entries.insert(paper.to_string(), refid.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cache, MemoryCache};
+
+    #[test]
+    fn unseen_paper_has_no_last_refid() {
+        let cache = MemoryCache::new();
+        assert_eq!(cache.last_refid("Politiken"), None);
+    }
+
+    #[test]
+    fn papers_are_tracked_independently() {
+        let mut cache = MemoryCache::new();
+        cache.set_last_refid("Politiken", "202607%");
+        cache.set_last_refid("Information", "202606%");
+
+        assert_eq!(cache.last_refid("Politiken"), Some("202607%".to_string()));
+        assert_eq!(cache.last_refid("Information"), Some("202606%".to_string()));
+    }
+
+    #[test]
+    fn set_last_refid_overwrites_the_previous_value() {
+        let mut cache = MemoryCache::new();
+        cache.set_last_refid("Politiken", "202606%");
+        cache.set_last_refid("Politiken", "202607%");
+
+        assert_eq!(cache.last_refid("Politiken"), Some("202607%".to_string()));
+    }
+}
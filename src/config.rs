@@ -0,0 +1,129 @@
+//! Loads paper definitions and device connection details from a TOML file
+//! (`kindlepaper.toml` by default, overridable via `KINDLEPAPER_CONFIG`).
+//! Device credentials may also come entirely from `KINDLEPAPER_DEVICE_*`
+//! environment variables.
+
+use std::env;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::Error;
+use std::path::Path;
+
+use toml::Value;
+use toml::value::Table;
+
+const DEFAULT_CONFIG_FILE: &'static str = "kindlepaper.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Android,
+    Ios,
+}
+
+#[derive(Debug)]
+pub struct PaperConfig {
+    pub name: String,
+    pub app_id: String,
+    pub select_stmt: String,
+    pub refid_stmt: String,
+    pub transport: Transport,
+}
+
+#[derive(Debug)]
+pub struct DeviceConfig {
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub password: String,
+    /// Template for the remote database path, with `{app_id}` substituted
+    /// for each paper's app id.
+    pub remote_path: String,
+}
+
+#[derive(Debug)]
+pub struct Config {
+    pub papers: Vec<PaperConfig>,
+    /// `None` when no paper uses the iOS transport, so Android-only users
+    /// never have to fill in a `[device]` block they'll never contact.
+    pub device: Option<DeviceConfig>,
+}
+
+impl DeviceConfig {
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    pub fn remote_path_for(&self, app_id: &str) -> String {
+        self.remote_path.replace("{app_id}", app_id)
+    }
+}
+
+pub fn load() -> Result<Config, Error> {
+    let path = env::var("KINDLEPAPER_CONFIG").unwrap_or(DEFAULT_CONFIG_FILE.to_string());
+    load_from(Path::new(&path))
+}
+
+pub fn load_from(path: &Path) -> Result<Config, Error> {
+    let mut f = try!(File::open(path));
+    let mut contents = String::new();
+    try!(f.read_to_string(&mut contents));
+
+    let value: Value = contents.parse()
+        .unwrap_or_else(|e| panic!("invalid config file {}: {}", path.display(), e));
+    let table = value.as_table().expect("config file must be a TOML table");
+
+    let papers: Vec<PaperConfig> = table.get("papers")
+        .and_then(Value::as_array)
+        .map(|papers| papers.iter().map(parse_paper).collect())
+        .unwrap_or_else(Vec::new);
+
+    let needs_device = papers.iter().any(|p| p.transport == Transport::Ios);
+    let device = parse_device(table.get("device").and_then(Value::as_table), needs_device);
+
+    Ok(Config { papers: papers, device: device })
+}
+
+fn parse_device(table: Option<&Table>, required: bool) -> Option<DeviceConfig> {
+    let get = |key: &str| table.and_then(|t| t.get(key)).and_then(Value::as_str).map(str::to_string);
+
+    let host = env::var("KINDLEPAPER_DEVICE_HOST").ok().or_else(|| get("host"));
+    let user = env::var("KINDLEPAPER_DEVICE_USER").ok().or_else(|| get("user"));
+    let password = env::var("KINDLEPAPER_DEVICE_PASSWORD").ok().or_else(|| get("password"));
+    let remote_path = get("remote_path");
+
+    if !required && (host.is_none() || user.is_none() || password.is_none() || remote_path.is_none()) {
+        return None;
+    }
+
+    let port = env::var("KINDLEPAPER_DEVICE_PORT").ok().and_then(|p| p.parse().ok())
+        .or_else(|| table.and_then(|t| t.get("port")).and_then(Value::as_integer).map(|p| p as u16))
+        .unwrap_or(22);
+
+    Some(DeviceConfig {
+        host: host.expect("device.host is required for iOS papers (set [device].host or KINDLEPAPER_DEVICE_HOST)"),
+        port: port,
+        user: user.expect("device.user is required for iOS papers (set [device].user or KINDLEPAPER_DEVICE_USER)"),
+        password: password.expect("device.password is required for iOS papers (set [device].password or KINDLEPAPER_DEVICE_PASSWORD)"),
+        remote_path: remote_path.expect("device.remote_path is required for iOS papers"),
+    })
+}
+
+fn parse_paper(value: &Value) -> PaperConfig {
+    let table = value.as_table().expect("each [[papers]] entry must be a table");
+    let get = |key: &str| table.get(key).and_then(Value::as_str)
+        .unwrap_or_else(|| panic!("papers.{} is required", key)).to_string();
+
+    let transport = match &*get("transport") {
+        "android" => Transport::Android,
+        "ios" => Transport::Ios,
+        other => panic!("unknown transport '{}', expected 'android' or 'ios'", other),
+    };
+
+    PaperConfig {
+        name: get("name"),
+        app_id: get("app_id"),
+        select_stmt: get("select_stmt"),
+        refid_stmt: get("refid_stmt"),
+        transport: transport,
+    }
+}
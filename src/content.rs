@@ -0,0 +1,223 @@
+//! Sanitizes the raw article body markup pulled from the reader apps'
+//! databases: loosely-formed HTML fragments with stray `<div class='h3'>`
+//! subheadings and embedded `<script>`/`<style>`/`<svg>`.
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+const SUPPRESSED_TAGS: &'static [&'static str] = &["script", "style", "svg"];
+
+/// HTML void elements: never have a closing tag, so a `<br>` written without
+/// a self-closing slash must not stay open on the stack waiting for a
+/// `</br>` that will never come (and that would otherwise consume the next
+/// unrelated closing tag instead).
+const VOID_TAGS: &'static [&'static str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input",
+    "link", "meta", "param", "source", "track", "wbr",
+];
+
+enum Open {
+    /// Inside a suppressed element (or a descendant of one): discard text.
+    Suppressed,
+    /// A `<div class="h3">` subheading, flattened into `<section><h3>...`.
+    H3,
+    /// Any other element: passed through verbatim under its own name.
+    Pass(String),
+}
+
+/// Converts one article's raw `content` column into sanitized section HTML,
+/// starting and ending inside an open `<section>` so callers can splice the
+/// result directly into the article's container.
+pub fn sanitize(raw: &str) -> String {
+    let trimmed = raw.trim_start_matches('\u{feff}');
+    let mut reader = Reader::from_str(trimmed);
+    reader.check_end_names(false);
+    reader.trim_text(false);
+
+    let mut out = String::from("<section>");
+    let mut buf = Vec::new();
+    let mut stack: Vec<Open> = Vec::new();
+
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(ref e)) => {
+                let void = VOID_TAGS.contains(&&*tag_name(e));
+                push_start(&mut stack, &mut out, e);
+                if void {
+                    pop_end(&mut stack, &mut out);
+                }
+            }
+            Ok(Event::Empty(ref e)) => {
+                push_start(&mut stack, &mut out, e);
+                pop_end(&mut stack, &mut out);
+            }
+            Ok(Event::End(_)) => {
+                pop_end(&mut stack, &mut out);
+            }
+            Ok(Event::Text(ref e)) => {
+                if !top_is_suppressed(&stack) {
+                    match e.unescape_and_decode(&reader) {
+                        Ok(text) => out.push_str(&text),
+                        Err(_) => out.push_str(&String::from_utf8_lossy(&e)),
+                    }
+                }
+            }
+            Ok(Event::Eof) => break,
+            Err(_) => {
+                // Unescaped entity or other malformed markup: keep whatever
+                // we've sanitized so far and append the untouched remainder
+                // as plain text rather than losing the rest of the article.
+                let pos = reader.buffer_position();
+                if pos < trimmed.len() {
+                    out.push_str(&trimmed[pos..]);
+                }
+                break;
+            }
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    out.push_str("</section>");
+
+    out
+}
+
+fn push_start(stack: &mut Vec<Open>, out: &mut String, e: &BytesStart) {
+    let name = tag_name(e);
+    let suppressed = top_is_suppressed(stack) || SUPPRESSED_TAGS.contains(&&*name);
+
+    if suppressed {
+        stack.push(Open::Suppressed);
+    } else if name == "div" && has_h3_class(e) {
+        out.push_str("</section><section><h3>");
+        stack.push(Open::H3);
+    } else {
+        out.push('<');
+        out.push_str(&name);
+        out.push('>');
+        stack.push(Open::Pass(name));
+    }
+}
+
+fn pop_end(stack: &mut Vec<Open>, out: &mut String) {
+    match stack.pop() {
+        Some(Open::Suppressed) => {}
+        Some(Open::H3) => out.push_str("</h3>"),
+        Some(Open::Pass(name)) => {
+            out.push_str("</");
+            out.push_str(&name);
+            out.push('>');
+        }
+        None => {}
+    }
+}
+
+fn top_is_suppressed(stack: &[Open]) -> bool {
+    match stack.last() {
+        Some(&Open::Suppressed) => true,
+        _ => false,
+    }
+}
+
+fn tag_name(e: &BytesStart) -> String {
+    String::from_utf8_lossy(e.name()).to_lowercase()
+}
+
+fn has_h3_class(e: &BytesStart) -> bool {
+    e.attributes().filter_map(|a| a.ok()).any(|a| {
+        a.key == b"class" && a.unescaped_value().map(|v| v == &b"h3"[..]).unwrap_or(false)
+    })
+}
+
+/// Extracts an article's plain reading text with all markup removed,
+/// dropping the text of suppressed elements too. Used to populate the
+/// full-text search archive, where ranking should see prose, not tags.
+pub fn plain_text(raw: &str) -> String {
+    let trimmed = raw.trim_start_matches('\u{feff}');
+    let mut reader = Reader::from_str(trimmed);
+    reader.check_end_names(false);
+    reader.trim_text(false);
+
+    let mut out = String::new();
+    let mut buf = Vec::new();
+    let mut skip_depth = 0usize;
+
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(ref e)) => {
+                if skip_depth > 0 || SUPPRESSED_TAGS.contains(&&*tag_name(e)) {
+                    skip_depth += 1;
+                }
+            }
+            Ok(Event::End(_)) => {
+                if skip_depth > 0 {
+                    skip_depth -= 1;
+                }
+            }
+            Ok(Event::Text(ref e)) => {
+                if skip_depth == 0 {
+                    match e.unescape_and_decode(&reader) {
+                        Ok(text) => out.push_str(&text),
+                        Err(_) => out.push_str(&String::from_utf8_lossy(&e)),
+                    }
+                }
+            }
+            Ok(Event::Eof) => break,
+            Err(_) => {
+                let pos = reader.buffer_position();
+                if pos < trimmed.len() {
+                    out.push_str(&trimmed[pos..]);
+                }
+                break;
+            }
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{plain_text, sanitize};
+
+    #[test]
+    fn wraps_leading_text_in_a_section() {
+        assert_eq!(
+            sanitize("intro text<div class='h3'>Heading</div>more text"),
+            "<section>intro text</section><section><h3>Heading</h3>more text</section>");
+    }
+
+    #[test]
+    fn unclosed_br_does_not_swallow_the_following_close_tag() {
+        assert_eq!(
+            sanitize("<p>Hello<br>World</p><div class='h3'>Sub</div>Tail"),
+            "<section><p>Hello<br></br>World</p></section><section><h3>Sub</h3>Tail</section>");
+    }
+
+    #[test]
+    fn sibling_paragraphs_stay_unnested_across_an_unclosed_br() {
+        assert_eq!(
+            sanitize("<p>Para1<br>line2</p><p>Para2</p>"),
+            "<section><p>Para1<br></br>line2</p><p>Para2</p></section>");
+    }
+
+    #[test]
+    fn suppresses_script_and_style_content() {
+        assert_eq!(
+            sanitize("before<script>alert(1)</script>after"),
+            "<section>beforeafter</section>");
+    }
+
+    #[test]
+    fn strips_markup_for_plain_text() {
+        assert_eq!(plain_text("<p>Hello <b>World</b></p>"), "Hello World");
+    }
+
+    #[test]
+    fn plain_text_drops_suppressed_element_text_too() {
+        assert_eq!(plain_text("keep<script>drop()</script>keep"), "keepkeep");
+    }
+}
@@ -0,0 +1,59 @@
+//! Packages the generated TOC/content/OPF into a valid EPUB3 container.
+//!
+//! EPUB is a zip archive with one hard rule: the `mimetype` entry must come
+//! first, stored uncompressed with no extra field, so that naive readers can
+//! sniff the format by looking at the first bytes of the zip. Everything
+//! else is located through `META-INF/container.xml`.
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{self, Error};
+use std::path::Path;
+
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+const MIMETYPE: &'static str = "application/epub+zip";
+
+/// Writes `out_path` as an EPUB3 archive containing `opf_path`, `toc_path`
+/// and `content_path`. `opf_path`'s file name is used as the OPF's
+/// `full-path` inside `container.xml`, so all three files are expected to
+/// sit flat at the archive root.
+pub fn write_epub(out_path: &Path, opf_path: &Path, toc_path: &Path, content_path: &Path) -> Result<(), Error> {
+    let file = try!(File::create(out_path));
+    let mut zip = ZipWriter::new(file);
+
+    let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+    try!(zip.start_file("mimetype", stored));
+    try!(zip.write_all(MIMETYPE.as_bytes()));
+
+    let deflated = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    let opf_name = file_name(opf_path);
+
+    try!(zip.start_file("META-INF/container.xml", deflated));
+    try!(write!(zip, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+<container version=\"1.0\" xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\">\n\
+  <rootfiles>\n\
+    <rootfile full-path=\"{}\" media-type=\"application/oebps-package+xml\"/>\n\
+  </rootfiles>\n\
+</container>", opf_name));
+
+    try!(add_file(&mut zip, &opf_name, opf_path, deflated));
+    try!(add_file(&mut zip, &file_name(toc_path), toc_path, deflated));
+    try!(add_file(&mut zip, &file_name(content_path), content_path, deflated));
+
+    try!(zip.finish());
+    Ok(())
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap().to_string_lossy().into_owned()
+}
+
+fn add_file<W: Write + io::Seek>(zip: &mut ZipWriter<W>, name: &str, path: &Path, options: FileOptions) -> Result<(), Error> {
+    let mut f = try!(File::open(path));
+    let mut contents = Vec::new();
+    try!(f.read_to_end(&mut contents));
+    try!(zip.start_file(name, options));
+    zip.write_all(&contents)
+}
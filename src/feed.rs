@@ -0,0 +1,156 @@
+//! Generates an OPDS (Atom) acquisition feed over everything `convert_papers`
+//! produces, backed by a small TOML catalog that keeps each book's `uuid`
+//! stable across runs.
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+
+use toml::Value;
+use toml::value::Table;
+use uuid::Uuid;
+
+const CATALOG_FILE: &'static str = "catalog.toml";
+const FEED_FILE: &'static str = "feed.xml";
+const AUTHOR: &'static str = "Andreas H. From";
+
+#[derive(Debug, Clone)]
+pub struct BookRecord {
+    pub name: String,
+    pub uuid: String,
+    pub modified: String,
+    pub description: String,
+    pub file_name: String,
+}
+
+pub struct Catalog {
+    dir: PathBuf,
+    books: Vec<BookRecord>,
+}
+
+impl Catalog {
+    /// Loads the catalog from `dir`'s `catalog.toml`, or starts an empty one
+    /// if this is the first run.
+    pub fn load(dir: &Path) -> Catalog {
+        let books = read_to_string(&dir.join(CATALOG_FILE))
+            .and_then(|s| s.parse::<Value>().ok())
+            .and_then(|v| v.as_table().and_then(|t| t.get("books").cloned()))
+            .and_then(|v| v.as_array().cloned())
+            .map(|arr| arr.iter().filter_map(parse_book).collect())
+            .unwrap_or_else(Vec::new);
+
+        Catalog { dir: dir.to_path_buf(), books: books }
+    }
+
+    /// Records (or updates) the entry for a generated book, reusing its
+    /// `uuid` across runs so the feed's identifiers stay stable.
+    pub fn record(&mut self, name: &str, file_name: &str, modified: &str, description: &str) {
+        if let Some(existing) = self.books.iter_mut().find(|b| b.name == name) {
+            existing.modified = modified.to_string();
+            existing.description = description.to_string();
+            existing.file_name = file_name.to_string();
+            return;
+        }
+
+        self.books.push(BookRecord {
+            name: name.to_string(),
+            uuid: Uuid::new_v4().to_string(),
+            modified: modified.to_string(),
+            description: description.to_string(),
+            file_name: file_name.to_string(),
+        });
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        let books: Vec<Value> = self.books.iter().map(book_to_value).collect();
+        let mut table = Table::new();
+        table.insert("books".to_string(), Value::Array(books));
+        let out = Value::Table(table).to_string();
+
+        let mut f = try!(File::create(self.dir.join(CATALOG_FILE)));
+        f.write_all(out.as_bytes())
+    }
+
+    pub fn write_feed(&self) -> Result<(), Error> {
+        let mut f = try!(File::create(self.dir.join(FEED_FILE)));
+
+        try!(write!(f, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"));
+        try!(write!(f, "<feed xmlns=\"http://www.w3.org/2005/Atom\" xmlns:dc=\"http://purl.org/dc/terms/\">\n"));
+        try!(write!(f, "  <id>urn:uuid:kindlepaper-catalog</id>\n"));
+        try!(write!(f, "  <title>kindlepaper</title>\n"));
+        try!(write!(f, "  <updated>{}</updated>\n", most_recent(&self.books)));
+        try!(write!(f, "  <link rel=\"self\" href=\"{}\" type=\"application/atom+xml;profile=opds-catalog\"/>\n", FEED_FILE));
+
+        for b in &self.books {
+            try!(write!(f, "  <entry>\n"));
+            try!(write!(f, "    <id>urn:uuid:{}</id>\n", b.uuid));
+            try!(write!(f, "    <title>{}</title>\n", escape_xml(&b.name)));
+            try!(write!(f, "    <updated>{}</updated>\n", b.modified));
+            try!(write!(f, "    <author><name>{}</name></author>\n", escape_xml(AUTHOR)));
+            try!(write!(f, "    <dc:publisher>{}</dc:publisher>\n", escape_xml(AUTHOR)));
+            try!(write!(f, "    <content type=\"text\">{}</content>\n", escape_xml(&b.description)));
+            try!(write!(f, "    <link rel=\"http://opds-spec.org/acquisition\" href=\"{}\" type=\"{}\"/>\n",
+                        escape_xml(&b.file_name), media_type(&b.file_name)));
+            try!(write!(f, "  </entry>\n"));
+        }
+
+        write!(f, "</feed>\n")
+    }
+}
+
+/// Escapes text for use as XML element content or attribute value.
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+     .replace('<', "&lt;")
+     .replace('>', "&gt;")
+     .replace('"', "&quot;")
+}
+
+fn most_recent(books: &[BookRecord]) -> &str {
+    books.iter().map(|b| b.modified.as_str()).max().unwrap_or("")
+}
+
+fn media_type(file_name: &str) -> &'static str {
+    if file_name.ends_with(".mobi") {
+        "application/x-mobipocket-ebook"
+    } else {
+        "application/epub+zip"
+    }
+}
+
+fn read_to_string(path: &Path) -> Option<String> {
+    let mut f = match File::open(path) {
+        Ok(f) => f,
+        Err(_) => return None,
+    };
+    let mut s = String::new();
+    match f.read_to_string(&mut s) {
+        Ok(_) => Some(s),
+        Err(_) => None,
+    }
+}
+
+fn book_to_value(b: &BookRecord) -> Value {
+    let mut table = Table::new();
+    table.insert("name".to_string(), Value::String(b.name.clone()));
+    table.insert("uuid".to_string(), Value::String(b.uuid.clone()));
+    table.insert("modified".to_string(), Value::String(b.modified.clone()));
+    table.insert("description".to_string(), Value::String(b.description.clone()));
+    table.insert("file_name".to_string(), Value::String(b.file_name.clone()));
+    Value::Table(table)
+}
+
+fn parse_book(value: &Value) -> Option<BookRecord> {
+    let table = match value.as_table() {
+        Some(t) => t,
+        None => return None,
+    };
+    let get = |key: &str| table.get(key).and_then(Value::as_str).map(str::to_string);
+
+    match (get("name"), get("uuid"), get("modified"), get("description"), get("file_name")) {
+        (Some(name), Some(uuid), Some(modified), Some(description), Some(file_name)) =>
+            Some(BookRecord { name: name, uuid: uuid, modified: modified, description: description, file_name: file_name }),
+        _ => None,
+    }
+}
@@ -1,6 +1,21 @@
 extern crate sqlite3;
 extern crate time;
 extern crate ssh2;
+extern crate zip;
+extern crate toml;
+extern crate quick_xml;
+extern crate rusqlite;
+extern crate uuid;
+
+mod epub;
+mod config;
+mod content;
+mod archive;
+mod feed;
+mod cache;
+
+use config::{PaperConfig, DeviceConfig, Transport};
+use cache::{Cache, SqliteCache};
 
 use std::io::prelude::*;
 use std::io::Error;
@@ -23,93 +38,95 @@ const DATE_FMT: &'static str = "%F"; // ISO 8601
 const OUT: &'static str = "papers";
 const TOC_FILE: &'static str = "toc.html";
 const CONTENT_FILE: &'static str = "content.html";
+const CACHE_FILE: &'static str = "cache.db";
 
 const EXTRACT_SCRIPT: &'static [u8; 122] =
     b"dd if=$1 bs=1 skip=24 | python -c \"import zlib,sys;sys.stdout.write(zlib.decompress(sys.stdin.read()))\" | tar -xvf - -C $2";
 
-const IPAD_IP: &'static str = "192.168.1.109:22";
-const IPAD_USER: &'static str = "root";
-const IPAD_PASSWORD: &'static str = "alpine";
-
 #[derive(Debug)]
 struct Article {
     title: String,
     byline: String,
     blurb: String,
     content: String,
+    refid: String,
 }
 
-#[derive(Debug)]
-struct Config {
-    name: String,
-    app_id: String,
-    select_stmt: String,
-    refid_stmt: String,
+#[derive(Debug, Clone, Copy)]
+enum OutputFormat {
+    Epub,
+    Mobi,
 }
 
-impl Config {
-    fn new(name: &str, app_id: &str, select_stmt: &str, refid_stmt: &str) -> Config {
-        Config {
-            name: name.to_string(),
-            app_id: app_id.to_string(),
-            select_stmt: select_stmt.to_string(),
-            refid_stmt: refid_stmt.to_string(),
-        }
+fn main() {
+    let argv: Vec<String> = args().collect();
+
+    if argv.get(1).map(String::as_str) == Some("search") {
+        let query = argv.get(2).expect("usage: kindlepaper search <query>");
+        return run_search(query);
     }
-}
 
-fn main() {
-    let mut args = args();
-    let ipad = args.len() > 1 && args.nth(1) == Some("ipad".to_string());
-    let papers;
-
-    if ipad {
-    papers = vec![
-        Config::new(
-            "Politiken",
-            "4C1B6602-BAFD-4582-8A9F-1B956C8C4D93",
-            "SELECT ZTITLE AS title, ZBYLINE AS byline, ZBLURB AS blurb, ZCONTENT AS content FROM ZARTICLE WHERE ZREFID LIKE ? ORDER BY ZORIGINPAGE, ZREFID",
-            "SELECT ZREFID AS refid FROM ZARTICLE ORDER BY Z_PK DESC LIMIT 1"),
-
-        Config::new(
-            "Information",
-            "9597F96C-52F4-4467-8D6F-5CC515FACE1E",
-            "SELECT ZTITLE AS title, ZAUTHOR AS byline, ZBLURB AS blurb, ZCONTENT AS content FROM ZARTICLE LEFT JOIN ZBYLINE ON ZARTICLE.Z_PK == ZBYLINE.ZARTICLE WHERE ZREFID LIKE ? ORDER BY ZORIGINPAGE, ZREFID",
-            "SELECT ZREFID AS refid FROM ZARTICLE ORDER BY Z_PK DESC LIMIT 1"),
-        ];
-
-        fetch_data_from_ipad(&papers).unwrap();
+    let format = if argv.iter().any(|a| a == "--mobi") {
+        OutputFormat::Mobi
     } else {
-        papers = vec![
-            Config::new(
-                "Politiken",
-                "dk.politiken.reader",
-                "SELECT title, byline, blurb, content FROM articles WHERE refid LIKE ?",
-                "SELECT refid FROM articles ORDER BY article_id DESC LIMIT 1"),
-
-            Config::new(
-                "Information",
-                "dk.information.areader",
-                "SELECT title, author AS byline, blurb, content FROM articles LEFT JOIN byline ON articles.article_id == byline.article_id WHERE refid LIKE ?",
-                "SELECT refid FROM articles ORDER BY article_id DESC LIMIT 1"),
-            ];
-
-        fetch_data_from_android(&papers).unwrap();
-    }
+        OutputFormat::Epub
+    };
+    let force = argv.iter().any(|a| a == "--force");
+
+    let cfg = config::load().unwrap_or_else(|e| panic!("failed to load config: {}", e));
 
     create_dir(OUT).ok();
-    convert_papers(&papers).unwrap();
+    let mut cache = SqliteCache::open(&Path::new(OUT).join(CACHE_FILE))
+        .unwrap_or_else(|e| panic!("failed to open cache: {}", e));
+
+    let (ios_papers, android_papers): (Vec<&PaperConfig>, Vec<&PaperConfig>) =
+        cfg.papers.iter().partition(|p| p.transport == Transport::Ios);
+
+    if !ios_papers.is_empty() {
+        let device = cfg.device.as_ref().expect("iOS papers are configured but [device] is missing");
+        fetch_data_from_ipad(&ios_papers, device, &cache, force).unwrap();
+    }
+    if !android_papers.is_empty() {
+        fetch_data_from_android(&android_papers, &cache, force).unwrap();
+    }
+
+    convert_papers(&cfg.papers, format, &mut cache, force).unwrap();
 }
 
 fn date_paper(name: &str) -> String {
     format!("{} - {}", name, now().strftime(DATE_FMT).unwrap())
 }
 
-fn convert_papers(papers: &[Config]) -> Result<(), Error> {
+fn run_search(query: &str) {
+    let conn = archive::open(Path::new(OUT)).unwrap_or_else(|e| panic!("failed to open archive: {}", e));
+    let hits = archive::search(&conn, query).unwrap_or_else(|e| panic!("search failed: {}", e));
+
+    for hit in hits {
+        println!("{} ({}, {})\n  {}", hit.title, hit.paper, hit.issue_date, hit.snippet);
+    }
+}
+
+fn convert_papers(papers: &[PaperConfig], format: OutputFormat, cache: &mut Cache, force: bool) -> Result<(), Error> {
+    let archive_conn = archive::open(Path::new(OUT)).unwrap_or_else(|e| panic!("failed to open archive: {}", e));
+    let mut catalog = feed::Catalog::load(Path::new(OUT));
+
     for paper in papers {
         let db = temp_dir().join(Path::new(&paper.name).with_extension("db"));
         if is_file(&db) {
-            let articles = fetch_articles(&db.to_string_lossy(), &paper.select_stmt, &paper.refid_stmt).unwrap();
+            let conn = open(&db.to_string_lossy(), None).unwrap();
+            let pattern = fetch_refid_pattern(&conn, &paper.refid_stmt).unwrap();
+
+            if !force && cache.last_refid(&paper.name).as_ref().map(|r| r == &pattern).unwrap_or(false) {
+                println!("{}: no new issue since last run, skipping", paper.name);
+                continue;
+            }
+
+            let articles = fetch_articles(&conn, &paper.select_stmt, &pattern).unwrap();
+            cache.set_last_refid(&paper.name, &pattern);
+
+            let issue_date = now().strftime(DATE_FMT).unwrap().to_string();
+            archive::index_articles(&archive_conn, &paper.name, &issue_date, &articles)
+                .unwrap_or_else(|e| panic!("failed to index articles: {}", e));
 
             let name = date_paper(&paper.name);
 
@@ -117,14 +134,40 @@ fn convert_papers(papers: &[Config]) -> Result<(), Error> {
             try!(write_articles(&articles, &name));
             try!(write_opf(&name));
 
-            kindlegen(&name);
+            let epub_out = Path::new(OUT).join(Path::new(&name).with_extension("epub"));
+            try!(epub::write_epub(
+                &epub_out,
+                &temp_dir().join(Path::new(&name).with_extension("opf")),
+                &temp_dir().join(TOC_FILE),
+                &temp_dir().join(CONTENT_FILE)));
+
+            let out_file = if let OutputFormat::Mobi = format {
+                kindlegen(&epub_out, &name);
+                Path::new(&name).with_extension("mobi")
+            } else {
+                Path::new(&name).with_extension("epub")
+            };
+
+            let description = format!("{} articles from {}, {}", articles.len(), paper.name, issue_date);
+            catalog.record(&name, &out_file.to_string_lossy(), &format!("{}T00:00:00Z", issue_date), &description);
         }
     }
 
+    try!(catalog.save());
+    try!(catalog.write_feed());
+
     Ok(())
 }
 
-fn fetch_data_from_android(papers: &[Config]) -> Result<(), Error> {
+fn fetch_data_from_android(papers: &[&PaperConfig], cache: &Cache, force: bool) -> Result<(), Error> {
+    let papers: Vec<&PaperConfig> = papers.iter().cloned()
+        .filter(|p| force || adb_refid_changed(p, cache))
+        .collect();
+
+    if papers.is_empty() {
+        return Ok(());
+    }
+
     let path = temp_dir().join(Path::new("papers").with_extension("ab"));
     let ids: Vec<&str> = papers.iter().map(|p| &*p.app_id).collect();
 
@@ -164,18 +207,45 @@ fn fetch_data_from_android(papers: &[Config]) -> Result<(), Error> {
     Ok(())
 }
 
-fn fetch_data_from_ipad(papers: &[Config]) -> Result<(), Error> {
-    let tcp = TcpStream::connect(IPAD_IP).unwrap();
+/// Checks an Android paper's on-device refid without paying for a full
+/// `adb backup`, via `run-as` (only works on debuggable app builds; when it
+/// doesn't, we conservatively assume the issue may have changed).
+fn adb_refid_changed(paper: &PaperConfig, cache: &Cache) -> bool {
+    let db_path = format!("/data/data/{}/databases/reader.db", paper.app_id);
+    let output = Command::new("adb")
+        .arg("shell")
+        .arg(format!("run-as {} sqlite3 {} \"{}\"", paper.app_id, db_path, paper.refid_stmt))
+        .output();
+
+    let refid = match output {
+        Ok(ref o) if o.status.success() => first_line(&String::from_utf8_lossy(&o.stdout)),
+        _ => None,
+    };
+
+    match refid {
+        Some(refid) => {
+            let pattern = refid_pattern_from(&refid);
+            cache.last_refid(&paper.name).map(|cached| cached != pattern).unwrap_or(true)
+        }
+        None => true,
+    }
+}
+
+fn fetch_data_from_ipad(papers: &[&PaperConfig], device: &DeviceConfig, cache: &Cache, force: bool) -> Result<(), Error> {
+    let tcp = TcpStream::connect(device.address()).unwrap();
     let mut sess = Session::new().unwrap();
     sess.handshake(&tcp).unwrap();
-    sess.userauth_password(IPAD_USER, IPAD_PASSWORD).unwrap();
+    sess.userauth_password(&device.user, &device.password).unwrap();
 
     for paper in papers {
+        let remote = Path::new(&device.remote_path_for(&paper.app_id)).to_path_buf();
+
+        if !force && !ios_refid_changed(&sess, &remote, &paper.refid_stmt, &paper.name, cache) {
+            println!("{}: no new issue on device, skipping transfer", paper.name);
+            continue;
+        }
+
         let out = temp_dir().join(&paper.name).with_extension("db");
-        let remote =
-            Path::new("/var/mobile/Applications")
-            .join(Path::new(&paper.app_id))
-            .join(Path::new("Documents/Reader.sqlite"));
 
         let (mut remote_file, _) = sess.scp_recv(&remote).unwrap();
         let mut contents = Vec::new();
@@ -188,21 +258,59 @@ fn fetch_data_from_ipad(papers: &[Config]) -> Result<(), Error> {
     Ok(())
 }
 
+/// Checks an iOS paper's on-device refid over the existing SSH session
+/// before paying for the full `scp` transfer.
+fn ios_refid_changed(sess: &Session, remote: &Path, refid_stmt: &str, paper: &str, cache: &Cache) -> bool {
+    let refid = remote_sqlite_refid(sess, remote, refid_stmt);
+
+    match refid {
+        Some(refid) => {
+            let pattern = refid_pattern_from(&refid);
+            cache.last_refid(paper).map(|cached| cached != pattern).unwrap_or(true)
+        }
+        None => true,
+    }
+}
+
+fn remote_sqlite_refid(sess: &Session, remote: &Path, refid_stmt: &str) -> Option<String> {
+    let mut channel = match sess.channel_session() {
+        Ok(c) => c,
+        Err(_) => return None,
+    };
+
+    if channel.exec(&format!("sqlite3 {} \"{}\"", remote.display(), refid_stmt)).is_err() {
+        return None;
+    }
+
+    let mut output = String::new();
+    if channel.read_to_string(&mut output).is_err() {
+        return None;
+    }
+    channel.wait_close().ok();
+
+    first_line(&output)
+}
+
+fn first_line(s: &str) -> Option<String> {
+    let line = s.lines().next().unwrap_or("").trim();
+    if line.is_empty() { None } else { Some(line.to_string()) }
+}
+
 fn is_file(path: &Path) -> bool {
     metadata(path).map(|m| m.is_file()).unwrap_or(false)
 }
 
-fn kindlegen(name: &str) {
-    let file = temp_dir().join(Path::new(name).with_extension("opf"));
-    let out = Path::new(name).with_extension("mobi");
+fn kindlegen(epub: &Path, name: &str) {
+    // kindlegen writes its output next to the source file, using the same stem.
+    let out = Path::new(OUT).join(Path::new(name).with_extension("mobi"));
     println!("{}", out.display());
 
     Command::new("kindlegen")
-        .arg(file)
+        .arg(epub)
         .output()
         .unwrap_or_else(|e| { panic!("failed to execute process: {}", e) });
 
-    fs::rename(temp_dir().join(&out), Path::new(OUT).join(&out)).unwrap();
+    fs::remove_file(epub).ok();
 }
 
 fn make_name(s: &str, idx: usize) -> String {
@@ -258,27 +366,8 @@ fn write_articles(articles: &[Article], title: &str) -> Result<(), Error> {
 
         try!(write!(f, "<address>{}</address>", a.byline));
 
-        try!(f.write_all(b"<section>"));
-
-        for line in a.content.lines() {
-            let start: String = FromIterator::from_iter(line.chars().take(16));
-            if start == "<div class='h3'>" {
-                try!(f.write_all(b"</section>"));
-                try!(f.write_all(b"<section>"));
-
-                let len = line.len();
-                let end = line.find("</div>").unwrap();
-
-                try!(write!(f, "<h3>{}</h3>", &line[16..end]));
-
-                try!(write!(f, "{}", &line[end+6..len]));
-            } else {
-                try!(write!(f, "{}", line));
-            }
-
-        }
+        try!(write!(f, "{}", content::sanitize(&a.content)));
 
-        try!(f.write_all(b"</section>"));
         try!(f.write_all(b"</article>"));
     }
 
@@ -301,15 +390,15 @@ fn fetch_refid_pattern(conn: &DatabaseConnection, refid_stmt: &str) -> SqliteRes
         None => panic!("no articles"),
     }
 
-    let sub_refid: String = FromIterator::from_iter(refid.chars().take(6));
-    Ok(sub_refid + "%")
+    Ok(refid_pattern_from(&refid))
 }
 
-fn fetch_articles(db_file: &str, select_stmt: &str, refid_stmt: &str) -> SqliteResult<Vec<Article>> {
-    let conn = try!(open(db_file, None));
-
-    let pattern = try!(fetch_refid_pattern(&conn, refid_stmt));
+fn refid_pattern_from(refid: &str) -> String {
+    let sub_refid: String = FromIterator::from_iter(refid.chars().take(6));
+    sub_refid + "%"
+}
 
+fn fetch_articles(conn: &DatabaseConnection, select_stmt: &str, pattern: &str) -> SqliteResult<Vec<Article>> {
     let mut stmt = try!(conn.prepare(select_stmt));
 
     let mut articles = vec!();
@@ -320,6 +409,7 @@ fn fetch_articles(db_file: &str, select_stmt: &str, refid_stmt: &str) -> SqliteR
                 byline: row.get("byline"),
                 blurb: row.get("blurb"),
                 content: row.get("content"),
+                refid: row.get("refid"),
             });
             Ok(())
         }));